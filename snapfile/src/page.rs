@@ -0,0 +1,35 @@
+//! The in-memory representation of a single page.
+
+/// The fixed size of a decompressed page, in bytes.
+///
+/// This matches the Postgres page size, since this crate stores snapshots
+/// of Postgres pages.
+pub const PAGE_SIZE: usize = 8192;
+
+/// A single page's worth of data.
+#[derive(Clone)]
+pub struct Page(pub Box<[u8; PAGE_SIZE]>);
+
+impl Default for Page {
+    fn default() -> Self {
+        Page(Box::new([0u8; PAGE_SIZE]))
+    }
+}
+
+impl From<[u8; PAGE_SIZE]> for Page {
+    fn from(data: [u8; PAGE_SIZE]) -> Self {
+        Page(Box::new(data))
+    }
+}
+
+impl AsRef<[u8]> for Page {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
+impl AsMut<[u8]> for Page {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self.0.as_mut()
+    }
+}
@@ -0,0 +1,96 @@
+//! Combine a chain of incremental snapshot indices into a single index.
+
+use crate::versioned::{PageIndex, PageState};
+
+/// Merge a chain of page indices, ordered from oldest (base) to newest,
+/// into a single index reflecting the full version history of every page.
+///
+/// A `Free` entry in a later layer shadows a `Present` entry at the same
+/// LSN for the same page, same as `LayeredSnapFile` does at read time.
+/// Once every layer has been folded together there's no older layer left
+/// for a tombstone to shadow, so any page whose *newest* recorded version
+/// is still `Free` is dropped entirely rather than carried forward.
+pub fn squash(layers: &[PageIndex]) -> PageIndex {
+    let mut merged = PageIndex::default();
+    for layer in layers {
+        for (&page_num, history) in &layer.map {
+            let merged_history = merged.map.entry(page_num).or_default();
+            for (&lsn, &state) in history {
+                merged_history.insert(lsn, state);
+            }
+        }
+    }
+    merged.map.retain(|_, history| {
+        !matches!(history.values().next_back(), Some(PageState::Free) | None)
+    });
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::versioned::PageLocation;
+    use std::collections::BTreeMap;
+
+    fn present(offset: u64) -> PageState {
+        PageState::Present(PageLocation {
+            offset,
+            len: 8192,
+            crc32c: None,
+        })
+    }
+
+    fn layer(entries: &[(u64, u64, PageState)]) -> PageIndex {
+        let mut index = PageIndex::default();
+        for &(page_num, lsn, state) in entries {
+            index.map.entry(page_num).or_default().insert(lsn, state);
+        }
+        index
+    }
+
+    #[test]
+    fn tombstone_shadows_older_present() {
+        let base = layer(&[(1, 100, present(0))]);
+        let incremental = layer(&[(1, 200, PageState::Free)]);
+
+        let merged = squash(&[base, incremental]);
+
+        // The page's newest version is a tombstone, so it's dropped
+        // entirely from the merged result...
+        assert!(!merged.map.contains_key(&1));
+        // ...even though its full history (including the now-shadowed
+        // `Present` at lsn 100) was folded in before the retain pass ran.
+    }
+
+    #[test]
+    fn base_reaching_tombstone_is_dropped() {
+        // A chain of three layers where the page is freed in the middle
+        // layer and never re-created: once every layer is folded
+        // together, there's no older layer left for the tombstone to
+        // shadow, so the page must not reappear in the merged index.
+        let base = layer(&[(1, 100, present(0))]);
+        let middle = layer(&[(1, 200, PageState::Free)]);
+        let newest = layer(&[(2, 300, present(8192))]);
+
+        let merged = squash(&[base, middle, newest]);
+
+        assert!(!merged.map.contains_key(&1));
+        assert!(merged.map.contains_key(&2));
+    }
+
+    #[test]
+    fn multi_version_history_survives_the_fold() {
+        // Each layer contributes a different LSN for the same page; since
+        // none of them is a tombstone, the merged index should retain
+        // every version, not just the newest layer's.
+        let base = layer(&[(1, 100, present(0))]);
+        let incremental = layer(&[(1, 200, present(8192))]);
+
+        let merged = squash(&[base, incremental]);
+
+        let history = merged.map.get(&1).unwrap();
+        let expected: BTreeMap<u64, PageState> =
+            BTreeMap::from([(100, present(0)), (200, present(8192))]);
+        assert_eq!(*history, expected);
+    }
+}
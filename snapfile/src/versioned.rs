@@ -0,0 +1,258 @@
+//! Versioned, on-disk data structures for the snapshot file format.
+//!
+//! Values in this module are serialized with [`aversion`], so that a
+//! `SnapFile` written by an older build of this crate can still be opened
+//! by a newer one: each type keeps its old shape around as a `VN` struct,
+//! and a `From<VN> for V(N+1)` impl describes how to upgrade it.
+
+use crate::page::PAGE_SIZE;
+use aversion::assign_message_ids;
+use bookfile::ChapterId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Magic number at the start of every snapshot file.
+pub const SNAPFILE_MAGIC: u64 = 0x5a_44_42_5f_53_4e_41_50;
+
+/// Chapter holding the serialized [`SnapFileMeta`].
+pub const CHAPTER_SNAP_META: ChapterId = ChapterId::new(1);
+/// Chapter holding raw (and, if enabled, compressed) page bytes.
+pub const CHAPTER_PAGES: ChapterId = ChapterId::new(2);
+/// Chapter holding the serialized [`PageIndex`].
+pub const CHAPTER_PAGE_INDEX: ChapterId = ChapterId::new(3);
+
+/// A pointer to the predecessor snapshot in an incremental chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Predecessor {
+    /// The predecessor's timeline id.
+    pub timeline: [u8; 16],
+    /// The predecessor's LSN.
+    pub lsn: u64,
+}
+
+/// The compression codec applied to every page in `CHAPTER_PAGES`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Codec {
+    /// Pages are stored uncompressed, at a fixed `PAGE_SIZE`-byte stride.
+    None,
+    /// Pages are compressed with zstd before being appended to the chapter.
+    Zstd,
+}
+
+/// Metadata describing a snapshot file: the original, pre-compression shape.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapFileMetaV1 {
+    pub timeline: [u8; 16],
+    pub predecessor: Option<Predecessor>,
+    pub lsn: u64,
+}
+
+/// Metadata describing a snapshot file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapFileMetaV2 {
+    /// The timeline this snapshot belongs to.
+    pub timeline: [u8; 16],
+    /// The predecessor snapshot, if this one is incremental.
+    pub predecessor: Option<Predecessor>,
+    /// The LSN at which this snapshot was taken.
+    pub lsn: u64,
+    /// The compression codec used for every page in `CHAPTER_PAGES`.
+    pub codec: Codec,
+    /// The decompressed size of every page, in bytes. Always `PAGE_SIZE`
+    /// today; kept explicit so a reader never has to assume it.
+    pub page_size: u32,
+}
+
+assign_message_ids! {
+    SnapFileMetaV1: 1,
+    SnapFileMetaV2: 2,
+}
+
+impl From<SnapFileMetaV1> for SnapFileMetaV2 {
+    fn from(old: SnapFileMetaV1) -> Self {
+        SnapFileMetaV2 {
+            timeline: old.timeline,
+            predecessor: old.predecessor,
+            lsn: old.lsn,
+            codec: Codec::None,
+            page_size: PAGE_SIZE as u32,
+        }
+    }
+}
+
+/// The latest version of the snapshot metadata.
+pub type SnapFileMeta = SnapFileMetaV2;
+
+/// The on-disk location of a page, back when every slot in `CHAPTER_PAGES`
+/// was a fixed `PAGE_SIZE`-byte block: a plain page-count index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageLocationV1(pub u64);
+
+/// The on-disk location of a single (possibly compressed) page: a byte
+/// offset into `CHAPTER_PAGES`, plus the number of bytes stored there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageLocationV2 {
+    /// Byte offset of the page's (possibly compressed) bytes.
+    pub offset: u64,
+    /// Number of bytes stored at `offset`.
+    pub len: u32,
+}
+
+impl From<PageLocationV1> for PageLocationV2 {
+    fn from(old: PageLocationV1) -> Self {
+        // Old snapshots use fixed PAGE_SIZE-byte slots, counted in pages.
+        PageLocationV2 {
+            offset: old.0 * PAGE_SIZE as u64,
+            len: PAGE_SIZE as u32,
+        }
+    }
+}
+
+/// The on-disk location of a single (possibly compressed) page, plus its
+/// checksum.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageLocationV3 {
+    /// Byte offset of the page's (possibly compressed) bytes.
+    pub offset: u64,
+    /// Number of bytes stored at `offset`.
+    pub len: u32,
+    /// CRC32C (Castagnoli) checksum of the page's decompressed bytes.
+    ///
+    /// `None` for pages written before checksums existed; there is nothing
+    /// to check against in that case, so verification is skipped.
+    pub crc32c: Option<u32>,
+}
+
+impl From<PageLocationV2> for PageLocationV3 {
+    fn from(old: PageLocationV2) -> Self {
+        PageLocationV3 {
+            offset: old.offset,
+            len: old.len,
+            crc32c: None,
+        }
+    }
+}
+
+/// The latest version of a page's on-disk location.
+pub type PageLocation = PageLocationV3;
+
+/// An index mapping page numbers to their (fixed-size) on-disk location.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageIndexV1 {
+    pub map: BTreeMap<u64, PageLocationV1>,
+}
+
+/// An index mapping page numbers to their on-disk location.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageIndexV2 {
+    /// Maps each stored page number to its on-disk location.
+    pub map: BTreeMap<u64, PageLocationV2>,
+}
+
+impl From<PageIndexV1> for PageIndexV2 {
+    fn from(old: PageIndexV1) -> Self {
+        PageIndexV2 {
+            map: old.map.into_iter().map(|(num, loc)| (num, loc.into())).collect(),
+        }
+    }
+}
+
+/// The state of a single page number within a snapshot's index, before
+/// checksums existed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PageStateV1 {
+    /// The page is stored in this snapshot at the given location.
+    Present(PageLocationV2),
+    /// The page was deleted since the predecessor snapshot.
+    Free,
+}
+
+/// The state of a single page number within a snapshot's index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PageStateV2 {
+    /// The page is stored in this snapshot at the given location.
+    Present(PageLocation),
+    /// The page was deleted since the predecessor snapshot.
+    ///
+    /// This is a tombstone: it must take precedence over a `Present` entry
+    /// for the same page number in any older (predecessor) layer.
+    Free,
+}
+
+impl From<PageStateV1> for PageStateV2 {
+    fn from(old: PageStateV1) -> Self {
+        match old {
+            PageStateV1::Present(location) => PageStateV2::Present(location.into()),
+            PageStateV1::Free => PageStateV2::Free,
+        }
+    }
+}
+
+/// The latest version of a page's state within a snapshot's index.
+pub type PageState = PageStateV2;
+
+/// An index mapping page numbers to their (pre-checksum) state.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageIndexV3 {
+    pub map: BTreeMap<u64, PageStateV1>,
+}
+
+/// An index mapping page numbers to their state in this snapshot.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageIndexV4 {
+    /// Maps each page number touched by this snapshot to its state.
+    pub map: BTreeMap<u64, PageState>,
+}
+
+/// An index mapping each page number to its full version history: every
+/// state it has held, keyed by the LSN at which that state took effect.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PageIndexV5 {
+    /// Maps each page number to its history, newest version last.
+    pub map: BTreeMap<u64, BTreeMap<u64, PageState>>,
+}
+
+assign_message_ids! {
+    PageIndexV1: 1,
+    PageIndexV2: 2,
+    PageIndexV3: 3,
+    PageIndexV4: 4,
+    PageIndexV5: 5,
+}
+
+impl From<PageIndexV2> for PageIndexV3 {
+    fn from(old: PageIndexV2) -> Self {
+        PageIndexV3 {
+            map: old
+                .map
+                .into_iter()
+                .map(|(num, loc)| (num, PageStateV1::Present(loc)))
+                .collect(),
+        }
+    }
+}
+
+impl From<PageIndexV3> for PageIndexV4 {
+    fn from(old: PageIndexV3) -> Self {
+        PageIndexV4 {
+            map: old.map.into_iter().map(|(num, state)| (num, state.into())).collect(),
+        }
+    }
+}
+
+impl From<PageIndexV4> for PageIndexV5 {
+    fn from(old: PageIndexV4) -> Self {
+        // Pre-history snapshots only ever held one version of each page;
+        // key it at LSN 0 so it is returned for a `read_page_at` at any LSN.
+        PageIndexV5 {
+            map: old
+                .map
+                .into_iter()
+                .map(|(num, state)| (num, BTreeMap::from([(0u64, state)])))
+                .collect(),
+        }
+    }
+}
+
+/// The latest version of the page index.
+pub type PageIndex = PageIndexV5;